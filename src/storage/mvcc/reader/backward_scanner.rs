@@ -0,0 +1,566 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp::Ordering;
+use std::u64;
+
+use kvproto::kvrpcpb::IsolationLevel;
+
+use storage::mvcc::write::{Write, WriteType};
+use storage::mvcc::{Lock, Result};
+use storage::{Cursor, CursorBuilder, Key, ScanMode, Snapshot, Statistics, Value};
+use storage::{CF_DEFAULT, CF_LOCK, CF_WRITE};
+
+use super::util::CheckLockResult;
+
+/// `BackwardScanner` factory.
+pub struct BackwardScannerBuilder<S: Snapshot> {
+    snapshot: S,
+    fill_cache: bool,
+    omit_value: bool,
+    isolation_level: IsolationLevel,
+    lower_bound: Option<Vec<u8>>,
+    upper_bound: Option<Vec<u8>>,
+    ts: u64,
+}
+
+impl<S: Snapshot> BackwardScannerBuilder<S> {
+    /// Initialize a new `BackwardScanner`
+    pub fn new(snapshot: S, ts: u64) -> Self {
+        Self {
+            snapshot,
+            fill_cache: true,
+            omit_value: false,
+            isolation_level: IsolationLevel::SI,
+            lower_bound: None,
+            upper_bound: None,
+            ts,
+        }
+    }
+
+    /// Set whether or not read operations should fill the cache.
+    ///
+    /// Defaults to `true`.
+    #[inline]
+    pub fn fill_cache(mut self, fill_cache: bool) -> Self {
+        self.fill_cache = fill_cache;
+        self
+    }
+
+    /// Set whether values of the user key should be omitted. When `omit_value` is `true`, the
+    /// length of returned value will be 0.
+    ///
+    /// Defaults to `false`.
+    #[inline]
+    pub fn omit_value(mut self, omit_value: bool) -> Self {
+        self.omit_value = omit_value;
+        self
+    }
+
+    /// Set the isolation level.
+    ///
+    /// Defaults to `IsolationLevel::SI`.
+    #[inline]
+    pub fn isolation_level(mut self, isolation_level: IsolationLevel) -> Self {
+        self.isolation_level = isolation_level;
+        self
+    }
+
+    /// Limit the range to `[lower_bound, upper_bound)` in which the `BackwardScanner` should
+    /// scan. `None` means unbounded.
+    ///
+    /// Default is `(None, None)`.
+    #[inline]
+    pub fn range(mut self, lower_bound: Option<Vec<u8>>, upper_bound: Option<Vec<u8>>) -> Self {
+        self.lower_bound = lower_bound;
+        self.upper_bound = upper_bound;
+        self
+    }
+
+    /// Build `BackwardScanner` from the current configuration.
+    pub fn build(self) -> Result<BackwardScanner<S>> {
+        // Both cursors are positioned in reverse, and the write cursor also seeks forward
+        // within a user key in `get()`, so neither can use the forward-only scan mode.
+        let lock_cursor = CursorBuilder::new(&self.snapshot, CF_LOCK)
+            .range(self.lower_bound.clone(), self.upper_bound.clone())
+            .fill_cache(self.fill_cache)
+            .scan_mode(ScanMode::Mixed)
+            .build()?;
+
+        let write_cursor = CursorBuilder::new(&self.snapshot, CF_WRITE)
+            .range(self.lower_bound.clone(), self.upper_bound.clone())
+            .fill_cache(self.fill_cache)
+            .scan_mode(ScanMode::Mixed)
+            .build()?;
+
+        Ok(BackwardScanner {
+            snapshot: self.snapshot,
+            fill_cache: self.fill_cache,
+            omit_value: self.omit_value,
+            isolation_level: self.isolation_level,
+            lower_bound: self.lower_bound,
+            upper_bound: self.upper_bound,
+            ts: self.ts,
+            lock_cursor,
+            write_cursor,
+            default_cursor: None,
+            is_started: false,
+            statistics: Statistics::default(),
+        })
+    }
+}
+
+/// This struct can be used to scan keys starting from the given user key (less than), in
+/// descending user key order.
+///
+/// Internally, for each key, rollbacks are ignored and smaller version will be tried. If the
+/// isolation level is SI, locks will be checked first.
+///
+/// Use `BackwardScannerBuilder` to build `BackwardScanner`.
+pub struct BackwardScanner<S: Snapshot> {
+    snapshot: S,
+    fill_cache: bool,
+    omit_value: bool,
+    isolation_level: IsolationLevel,
+
+    /// `lower_bound` and `upper_bound` is used to create `default_cursor`. `upper_bound`
+    /// is used in initial seek as well. They will be consumed after `default_cursor` is being
+    /// created.
+    lower_bound: Option<Vec<u8>>,
+    upper_bound: Option<Vec<u8>>,
+
+    ts: u64,
+
+    lock_cursor: Cursor<S::Iter>,
+    write_cursor: Cursor<S::Iter>,
+
+    /// `default cursor` is lazy created only when it's needed.
+    default_cursor: Option<Cursor<S::Iter>>,
+
+    /// Is iteration started
+    is_started: bool,
+
+    statistics: Statistics,
+}
+
+impl<S: Snapshot> BackwardScanner<S> {
+    /// Take out and reset the statistics collected so far.
+    pub fn take_statistics(&mut self) -> Statistics {
+        ::std::mem::replace(&mut self.statistics, Statistics::default())
+    }
+
+    /// Get the next key-value pair, in backward order.
+    pub fn read_next(&mut self) -> Result<Option<(Key, Value)>> {
+        if !self.is_started {
+            if self.upper_bound.is_some() {
+                // TODO: `seek_for_prev_to_last` is better, however it has performance issues
+                // currently.
+                self.write_cursor.seek_for_prev(
+                    &Key::from_encoded(self.upper_bound.as_ref().unwrap().clone()),
+                    &mut self.statistics.write,
+                )?;
+                self.lock_cursor.seek_for_prev(
+                    &Key::from_encoded(self.upper_bound.as_ref().unwrap().clone()),
+                    &mut self.statistics.lock,
+                )?;
+            } else {
+                self.write_cursor
+                    .seek_to_last(&mut self.statistics.write);
+                self.lock_cursor.seek_to_last(&mut self.statistics.lock);
+            }
+            self.is_started = true;
+        }
+
+        // The general idea is to simultaneously step write cursor and lock cursor, in reverse.
+
+        loop {
+            // `current_user_key` is `max(user_key(write_cursor), lock_cursor)`, indicating the
+            // encoded user key we are currently dealing with. It is the mirror image of the
+            // `min` used by `ForwardScanner`, because we are walking towards smaller user keys.
+            let (current_user_key, has_write, has_lock) = {
+                let w_key = if self.write_cursor.valid() {
+                    Some(self.write_cursor.key(&mut self.statistics.write))
+                } else {
+                    None
+                };
+                let l_key = if self.lock_cursor.valid() {
+                    Some(self.lock_cursor.key(&mut self.statistics.lock))
+                } else {
+                    None
+                };
+
+                let res = match (w_key, l_key) {
+                    (None, None) => {
+                        // Both cursors yield `None`: we know that there is nothing remaining.
+                        return Ok(None);
+                    }
+                    (None, Some(k)) => (k, false, true),
+                    (Some(k), None) => (Key::truncate_ts_for(k)?, true, false),
+                    (Some(wk), Some(lk)) => {
+                        let write_user_key = Key::truncate_ts_for(wk)?;
+                        match write_user_key.cmp(lk) {
+                            Ordering::Greater => (write_user_key, true, false),
+                            Ordering::Less => (lk, false, true),
+                            Ordering::Equal => (lk, true, true),
+                        }
+                    }
+                };
+
+                (Key::from_encoded_slice(res.0), res.1, res.2)
+            };
+
+            let mut result = Ok(None);
+            let mut get_ts = self.ts;
+
+            if has_lock {
+                match self.isolation_level {
+                    IsolationLevel::SI => {
+                        assert!(self.lock_cursor.valid());
+                        let lock = {
+                            let lock_value = self.lock_cursor.value(&mut self.statistics.lock);
+                            Lock::parse(lock_value)?
+                        };
+                        match super::util::check_lock(&current_user_key, self.ts, &lock)? {
+                            CheckLockResult::Locked(e) => result = Err(e),
+                            CheckLockResult::NotLocked => {}
+                            CheckLockResult::Ignored(ts) => get_ts = ts,
+                        }
+                    }
+                    IsolationLevel::RC => {}
+                }
+                self.lock_cursor.prev(&mut self.statistics.lock);
+            }
+            if has_write {
+                if result.is_ok() {
+                    result = self.get(&current_user_key, get_ts);
+                }
+                // Unlike `ForwardScanner`, after `self.get()` the write cursor may be pointing
+                // at an arbitrary version of `current_user_key` (we had to seek forward within
+                // the key to resolve the version). We always move it back to the previous user
+                // key explicitly.
+                self.move_write_cursor_to_prev_user_key(&current_user_key)?;
+            }
+
+            if let Some(v) = result? {
+                return Ok(Some((current_user_key, v)));
+            }
+        }
+    }
+
+    /// Attempt to get the value of a key specified by `user_key` and `ts`. This function
+    /// requires that the write cursor is currently pointing to the most recent (by `prev()`
+    /// order, i.e. smallest `commit_ts` among those not yet visited) write of `user_key`.
+    ///
+    /// Unlike `ForwardScanner::get`, within one user key, `prev()` walks writes in *increasing*
+    /// `commit_ts` order (since the underlying encoding sorts them descending and we are
+    /// iterating in reverse). So we cannot simply `next()` towards the desired version; instead
+    /// we seek forward to `${user_key}_${ts}`, which lands on the write with the largest
+    /// `commit_ts` that is still `<= ts`.
+    #[inline]
+    fn get(&mut self, user_key: &Key, ts: u64) -> Result<Option<Value>> {
+        assert!(self.write_cursor.valid());
+
+        self.write_cursor
+            .seek(&user_key.clone().append_ts(ts), &mut self.statistics.write)?;
+        if !self.write_cursor.valid() {
+            // Reached the end of the key space while seeking forward; there is no visible
+            // version at or below `ts`.
+            return Ok(None);
+        }
+
+        loop {
+            let current_key = self.write_cursor.key(&mut self.statistics.write);
+            if !Key::is_user_key_eq(current_key, user_key.encoded().as_slice()) {
+                // Seeking forward walked past `user_key` entirely: no visible version.
+                return Ok(None);
+            }
+            if Key::decode_ts_from(current_key)? > ts {
+                // Still newer than what we want; continue stepping forward within the key.
+                self.write_cursor.next(&mut self.statistics.write);
+                if !self.write_cursor.valid() {
+                    return Ok(None);
+                }
+                continue;
+            }
+
+            let write = Write::parse(self.write_cursor.value(&mut self.statistics.write))?;
+            self.statistics.write.processed += 1;
+
+            match write.write_type {
+                WriteType::Put => return Ok(Some(self.load_data_by_write(write, user_key)?)),
+                WriteType::Delete => return Ok(None),
+                WriteType::Lock | WriteType::Rollback => {
+                    // Continue towards an older version of the same key.
+                    self.write_cursor.next(&mut self.statistics.write);
+                    if !self.write_cursor.valid() {
+                        return Ok(None);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Load the value by the given `write`. If value is carried in `write`, it will be returned
+    /// directly. Otherwise there will be a default CF look up.
+    ///
+    /// The implementation is the same as `ForwardScanner::load_data_by_write`.
+    #[inline]
+    fn load_data_by_write(&mut self, write: Write, user_key: &Key) -> Result<Value> {
+        if self.omit_value {
+            return Ok(vec![]);
+        }
+        match write.short_value {
+            Some(value) => Ok(value),
+            None => {
+                self.ensure_default_cursor()?;
+                let value = super::util::near_load_data_by_write(
+                    &mut self.default_cursor.as_mut().unwrap(),
+                    user_key,
+                    write,
+                    &mut self.statistics,
+                )?;
+                Ok(value)
+            }
+        }
+    }
+
+    /// After `self.get()`, the write cursor may be pointing anywhere within `current_user_key`
+    /// (wherever the forward seek for the resolved version landed), or already past it. Move it
+    /// to some write of the previous user key (the exact version doesn't matter, `self.get()`
+    /// will seek to the one it needs), so the next loop iteration sees a consistent starting
+    /// point.
+    ///
+    /// We do this by seeking (in reverse) to `${current_user_key}_${MAX_TS}`, which encodes to a
+    /// position smaller than every actual write of `current_user_key` (since real commit
+    /// timestamps are always less than `MAX_TS`) but not smaller than any write of the previous
+    /// user key. `seek_for_prev` therefore already lands directly on the previous user key's
+    /// oldest version, with no additional step. Unlike the forward scanner's
+    /// `move_write_cursor_to_next_user_key`, no `next()`/`prev()` stepping is tried before the
+    /// seek.
+    #[inline]
+    fn move_write_cursor_to_prev_user_key(&mut self, current_user_key: &Key) -> Result<()> {
+        self.write_cursor.internal_seek_for_prev(
+            &current_user_key.clone().append_ts(u64::MAX),
+            &mut self.statistics.write,
+        )?;
+        Ok(())
+    }
+
+    /// Create the default cursor if it doesn't exist.
+    #[inline]
+    fn ensure_default_cursor(&mut self) -> Result<()> {
+        if self.default_cursor.is_some() {
+            return Ok(());
+        }
+        // Values are loaded in descending user key order, so the default cursor must be able to
+        // move backward as well.
+        let cursor = CursorBuilder::new(&self.snapshot, CF_DEFAULT)
+            .range(self.lower_bound.take(), self.upper_bound.take())
+            .fill_cache(self.fill_cache)
+            .scan_mode(ScanMode::Mixed)
+            .build()?;
+        self.default_cursor = Some(cursor);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use storage::mvcc::tests::*;
+    use storage::{Engine, TestEngineBuilder};
+
+    fn create_snapshot<E: Engine>(engine: &E) -> E::Snap {
+        engine.snapshot(&Default::default()).unwrap()
+    }
+
+    #[test]
+    fn test_basic() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+
+        // Generate 3 versions for `a`, committed at 10, 20 and 30.
+        must_prewrite_put(&engine, b"a", b"a_v1", b"a", 5);
+        must_commit(&engine, b"a", 5, 10);
+        must_prewrite_put(&engine, b"a", b"a_v2", b"a", 15);
+        must_commit(&engine, b"a", 15, 20);
+        must_prewrite_put(&engine, b"a", b"a_v3", b"a", 25);
+        must_commit(&engine, b"a", 25, 30);
+
+        // `b` only has a single version.
+        must_prewrite_put(&engine, b"b", b"b_v1", b"b", 10);
+        must_commit(&engine, b"b", 10, 15);
+
+        let snapshot = create_snapshot(&engine);
+        let mut scanner = BackwardScannerBuilder::new(snapshot, 100)
+            .build()
+            .unwrap();
+
+        // `b` sorts after `a`, so it is emitted first in backward order.
+        assert_eq!(
+            scanner.read_next().unwrap(),
+            Some((Key::from_raw(b"b"), b"b_v1".to_vec()))
+        );
+        // The newest committed version of `a` (30) is visible.
+        assert_eq!(
+            scanner.read_next().unwrap(),
+            Some((Key::from_raw(b"a"), b"a_v3".to_vec()))
+        );
+        assert_eq!(scanner.read_next().unwrap(), None);
+    }
+
+    #[test]
+    fn test_resolves_older_version_at_ts() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+
+        must_prewrite_put(&engine, b"a", b"a_v1", b"a", 5);
+        must_commit(&engine, b"a", 5, 10);
+        must_prewrite_put(&engine, b"a", b"a_v2", b"a", 15);
+        must_commit(&engine, b"a", 15, 20);
+
+        let snapshot = create_snapshot(&engine);
+        // Read at ts=12: only the version committed at 10 is visible.
+        let mut scanner = BackwardScannerBuilder::new(snapshot, 12)
+            .build()
+            .unwrap();
+        assert_eq!(
+            scanner.read_next().unwrap(),
+            Some((Key::from_raw(b"a"), b"a_v1".to_vec()))
+        );
+        assert_eq!(scanner.read_next().unwrap(), None);
+    }
+
+    #[test]
+    fn test_delete() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+
+        must_prewrite_put(&engine, b"a", b"a_v1", b"a", 5);
+        must_commit(&engine, b"a", 5, 10);
+        must_prewrite_delete(&engine, b"a", b"a", 15);
+        must_commit(&engine, b"a", 15, 20);
+
+        must_prewrite_put(&engine, b"b", b"b_v1", b"b", 10);
+        must_commit(&engine, b"b", 10, 15);
+
+        let snapshot = create_snapshot(&engine);
+        let mut scanner = BackwardScannerBuilder::new(snapshot, 100)
+            .build()
+            .unwrap();
+
+        // `b` sorts after `a`, so it is emitted first. `a`'s newest version is a tombstone: it
+        // yields no value, and there is no user key before `a`, so the scan ends.
+        assert_eq!(
+            scanner.read_next().unwrap(),
+            Some((Key::from_raw(b"b"), b"b_v1".to_vec()))
+        );
+        assert_eq!(scanner.read_next().unwrap(), None);
+    }
+
+    #[test]
+    fn test_lock_and_rollback_are_skipped() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+
+        must_prewrite_put(&engine, b"a", b"a_v1", b"a", 5);
+        must_commit(&engine, b"a", 5, 10);
+        // A prewrite that gets rolled back leaves a `Rollback` write record, not a value.
+        must_prewrite_put(&engine, b"a", b"a_v2", b"a", 15);
+        must_rollback(&engine, b"a", 15);
+        // A lock-only write (e.g. from a pessimistic lock) also carries no value.
+        must_prewrite_lock(&engine, b"a", b"a", 25);
+        must_commit(&engine, b"a", 25, 30);
+
+        let snapshot = create_snapshot(&engine);
+        let mut scanner = BackwardScannerBuilder::new(snapshot, 100)
+            .build()
+            .unwrap();
+
+        // Both the lock and rollback writes are skipped; the visible value is the older `Put`.
+        assert_eq!(
+            scanner.read_next().unwrap(),
+            Some((Key::from_raw(b"a"), b"a_v1".to_vec()))
+        );
+        assert_eq!(scanner.read_next().unwrap(), None);
+    }
+
+    #[test]
+    fn test_si_lock_conflict() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+
+        must_prewrite_put(&engine, b"a", b"a_v1", b"a", 5);
+        must_commit(&engine, b"a", 5, 10);
+        must_prewrite_put(&engine, b"b", b"b_v1", b"b", 10);
+        must_commit(&engine, b"b", 10, 15);
+        // Leave an un-committed lock on `b` with a start_ts visible to our read.
+        must_prewrite_put(&engine, b"b", b"b_v2", b"b", 20);
+
+        let snapshot = create_snapshot(&engine);
+        let mut scanner = BackwardScannerBuilder::new(snapshot, 100)
+            .build()
+            .unwrap();
+
+        // `b` is locked at a ts smaller than our read ts: under SI this must surface as a
+        // conflict rather than silently falling back to an older version.
+        scanner.read_next().unwrap_err();
+    }
+
+    #[test]
+    fn test_rc_ignores_lock() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+
+        must_prewrite_put(&engine, b"b", b"b_v1", b"b", 10);
+        must_commit(&engine, b"b", 10, 15);
+        must_prewrite_put(&engine, b"b", b"b_v2", b"b", 20);
+
+        let snapshot = create_snapshot(&engine);
+        let mut scanner = BackwardScannerBuilder::new(snapshot, 100)
+            .isolation_level(IsolationLevel::RC)
+            .build()
+            .unwrap();
+
+        // Under RC, the pending lock does not block the read; the last committed version wins.
+        assert_eq!(
+            scanner.read_next().unwrap(),
+            Some((Key::from_raw(b"b"), b"b_v1".to_vec()))
+        );
+        assert_eq!(scanner.read_next().unwrap(), None);
+    }
+
+    #[test]
+    fn test_range_bounds() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+
+        for (key, value) in &[(b"a" as &[u8], b"a_v" as &[u8]), (b"b", b"b_v"), (b"c", b"c_v")] {
+            must_prewrite_put(&engine, *key, *value, *key, 5);
+            must_commit(&engine, *key, 5, 10);
+        }
+
+        let snapshot = create_snapshot(&engine);
+        let mut scanner = BackwardScannerBuilder::new(snapshot, 100)
+            .range(
+                Some(Key::from_raw(b"a").into_encoded()),
+                Some(Key::from_raw(b"c").into_encoded()),
+            )
+            .build()
+            .unwrap();
+
+        // Upper bound `c` is exclusive, lower bound `a` is inclusive.
+        assert_eq!(
+            scanner.read_next().unwrap(),
+            Some((Key::from_raw(b"b"), b"b_v".to_vec()))
+        );
+        assert_eq!(
+            scanner.read_next().unwrap(),
+            Some((Key::from_raw(b"a"), b"a_v".to_vec()))
+        );
+        assert_eq!(scanner.read_next().unwrap(), None);
+    }
+}
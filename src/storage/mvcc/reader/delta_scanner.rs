@@ -0,0 +1,477 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::VecDeque;
+
+use storage::engine::SEEK_BOUND;
+use storage::mvcc::write::{Write, WriteType};
+use storage::mvcc::Result;
+use storage::{Cursor, CursorBuilder, Key, Snapshot, Statistics, Value};
+use storage::{CF_DEFAULT, CF_WRITE};
+
+/// `DeltaScanner` factory.
+pub struct DeltaScannerBuilder<S: Snapshot> {
+    snapshot: S,
+    fill_cache: bool,
+    omit_value: bool,
+    lower_bound: Option<Vec<u8>>,
+    upper_bound: Option<Vec<u8>>,
+    start_ts: u64,
+    end_ts: u64,
+}
+
+impl<S: Snapshot> DeltaScannerBuilder<S> {
+    /// Initialize a new `DeltaScanner` that emits every `Put` and `Delete` write committed in
+    /// `[start_ts, end_ts)`.
+    pub fn new(snapshot: S, start_ts: u64, end_ts: u64) -> Self {
+        Self {
+            snapshot,
+            fill_cache: true,
+            omit_value: false,
+            lower_bound: None,
+            upper_bound: None,
+            start_ts,
+            end_ts,
+        }
+    }
+
+    /// Set whether or not read operations should fill the cache.
+    ///
+    /// Defaults to `true`.
+    #[inline]
+    pub fn fill_cache(mut self, fill_cache: bool) -> Self {
+        self.fill_cache = fill_cache;
+        self
+    }
+
+    /// Set whether values of the user key should be omitted. When `omit_value` is `true`, the
+    /// length of returned value will be 0.
+    ///
+    /// Defaults to `false`.
+    #[inline]
+    pub fn omit_value(mut self, omit_value: bool) -> Self {
+        self.omit_value = omit_value;
+        self
+    }
+
+    /// Limit the range to `[lower_bound, upper_bound)` in which the `DeltaScanner` should scan.
+    /// `None` means unbounded.
+    ///
+    /// Default is `(None, None)`.
+    #[inline]
+    pub fn range(mut self, lower_bound: Option<Vec<u8>>, upper_bound: Option<Vec<u8>>) -> Self {
+        self.lower_bound = lower_bound;
+        self.upper_bound = upper_bound;
+        self
+    }
+
+    /// Build `DeltaScanner` from the current configuration.
+    pub fn build(self) -> Result<DeltaScanner<S>> {
+        let write_cursor = CursorBuilder::new(&self.snapshot, CF_WRITE)
+            .range(self.lower_bound.clone(), self.upper_bound.clone())
+            .fill_cache(self.fill_cache)
+            .build()?;
+
+        Ok(DeltaScanner {
+            snapshot: self.snapshot,
+            fill_cache: self.fill_cache,
+            omit_value: self.omit_value,
+            lower_bound: self.lower_bound,
+            upper_bound: self.upper_bound,
+            start_ts: self.start_ts,
+            end_ts: self.end_ts,
+            write_cursor,
+            default_cursor: None,
+            pending: VecDeque::new(),
+            is_started: false,
+            statistics: Statistics::default(),
+        })
+    }
+}
+
+/// A single entry of a `DeltaScanner`: the user key, the `commit_ts` it was written at, the kind
+/// of write, and the value (`None` for `Delete`, and for `Lock`/`Rollback` the entry is not
+/// emitted at all).
+pub type DeltaEntry = (Key, u64, WriteType, Option<Value>);
+
+/// This struct scans every committed write of a user key whose `commit_ts` falls in the
+/// half-open window `[start_ts, end_ts)`, in forward user-key order and, within a user key, in
+/// descending `commit_ts` order (newest-in-window first). Unlike `ForwardScanner`, which
+/// resolves a single visible version per key, this is the primitive that feeds an external
+/// change-capture or incremental-backup stream: it does not deduplicate or apply snapshot
+/// isolation, it simply replays the write history inside the window. `Lock` and `Rollback`
+/// writes carry no user-visible change, so they are skipped rather than emitted.
+///
+/// Use `DeltaScannerBuilder` to build `DeltaScanner`.
+pub struct DeltaScanner<S: Snapshot> {
+    snapshot: S,
+    fill_cache: bool,
+    omit_value: bool,
+
+    /// `lower_bound` and `upper_bound` is used to create `default_cursor`. `lower_bound`
+    /// is used in initial seek as well. They will be consumed after `default_cursor` is being
+    /// created.
+    lower_bound: Option<Vec<u8>>,
+    upper_bound: Option<Vec<u8>>,
+
+    start_ts: u64,
+    end_ts: u64,
+
+    write_cursor: Cursor<S::Iter>,
+
+    /// `default cursor` is lazy created only when it's needed.
+    default_cursor: Option<Cursor<S::Iter>>,
+
+    /// Writes of the user key currently being drained, buffered so a single user key with
+    /// several versions in the window can be returned one `read_next()` call at a time.
+    pending: VecDeque<DeltaEntry>,
+
+    /// Is iteration started
+    is_started: bool,
+
+    statistics: Statistics,
+}
+
+impl<S: Snapshot> DeltaScanner<S> {
+    /// Take out and reset the statistics collected so far.
+    pub fn take_statistics(&mut self) -> Statistics {
+        ::std::mem::replace(&mut self.statistics, Statistics::default())
+    }
+
+    /// Get the next write in the delta, in forward user-key order.
+    pub fn read_next(&mut self) -> Result<Option<DeltaEntry>> {
+        if !self.is_started {
+            if self.lower_bound.is_some() {
+                // TODO: We can eliminate clones here.
+                self.write_cursor.seek(
+                    &Key::from_encoded(self.lower_bound.as_ref().unwrap().clone()),
+                    &mut self.statistics.write,
+                )?;
+            } else {
+                self.write_cursor.seek_to_first(&mut self.statistics.write);
+            }
+            self.is_started = true;
+        }
+
+        loop {
+            if let Some(entry) = self.pending.pop_front() {
+                return Ok(Some(entry));
+            }
+            if !self.write_cursor.valid() {
+                return Ok(None);
+            }
+            let current_user_key = {
+                let key = self.write_cursor.key(&mut self.statistics.write);
+                Key::from_encoded_slice(Key::truncate_ts_for(key)?)
+            };
+            self.collect_versions_in_window(&current_user_key)?;
+        }
+    }
+
+    /// Collect every write of `user_key` whose `commit_ts` falls in `[self.start_ts,
+    /// self.end_ts)` into `self.pending`, then leave the write cursor pointing at the next user
+    /// key (or out of bound). Requires that the write cursor is currently pointing to the
+    /// newest (largest `commit_ts`) write of `user_key`.
+    fn collect_versions_in_window(&mut self, user_key: &Key) -> Result<()> {
+        loop {
+            if !self.write_cursor.valid() {
+                break;
+            }
+            let current_key = self.write_cursor.key(&mut self.statistics.write);
+            if !Key::is_user_key_eq(current_key, user_key.encoded().as_slice()) {
+                // Moved onto another user key.
+                break;
+            }
+            let commit_ts = Key::decode_ts_from(current_key)?;
+            if commit_ts < self.start_ts {
+                // Versions only get older from here on for this user key; nothing more of
+                // `user_key` can be in the window.
+                break;
+            }
+            if commit_ts >= self.end_ts {
+                // Too new for the window; skip towards an older version of the same key.
+                self.write_cursor.next(&mut self.statistics.write);
+                continue;
+            }
+
+            let write = Write::parse(self.write_cursor.value(&mut self.statistics.write))?;
+            self.statistics.write.processed += 1;
+
+            match write.write_type {
+                WriteType::Lock | WriteType::Rollback => {
+                    // Carries no user-visible change.
+                }
+                WriteType::Delete => {
+                    self.pending
+                        .push_back((user_key.clone(), commit_ts, WriteType::Delete, None));
+                }
+                WriteType::Put => {
+                    let value = self.load_data_by_write(write, user_key)?;
+                    self.pending
+                        .push_back((user_key.clone(), commit_ts, WriteType::Put, Some(value)));
+                }
+            }
+
+            self.write_cursor.next(&mut self.statistics.write);
+        }
+
+        self.move_write_cursor_to_next_user_key(user_key)
+    }
+
+    /// Load the value by the given `write`. If value is carried in `write`, it will be returned
+    /// directly. Otherwise there will be a default CF look up.
+    ///
+    /// The implementation is the same as `ForwardScanner::load_data_by_write`.
+    #[inline]
+    fn load_data_by_write(&mut self, write: Write, user_key: &Key) -> Result<Value> {
+        if self.omit_value {
+            return Ok(vec![]);
+        }
+        match write.short_value {
+            Some(value) => {
+                // Value is carried in `write`.
+                Ok(value)
+            }
+            None => {
+                // Value is in the default CF.
+                self.ensure_default_cursor()?;
+                let value = super::util::near_load_data_by_write(
+                    &mut self.default_cursor.as_mut().unwrap(),
+                    user_key,
+                    write,
+                    &mut self.statistics,
+                )?;
+                Ok(value)
+            }
+        }
+    }
+
+    /// After `collect_versions_in_window`, the write cursor may still be pointing at an
+    /// out-of-window (too old) write of `current_user_key`, or already past it. Step it until we
+    /// meet a new key. We first try to `next()` a few times. If still not reaching another user
+    /// key, we `seek()`.
+    ///
+    /// The implementation is the same as `ForwardScanner::move_write_cursor_to_next_user_key`.
+    #[inline]
+    fn move_write_cursor_to_next_user_key(&mut self, current_user_key: &Key) -> Result<()> {
+        for i in 0..SEEK_BOUND {
+            if i > 0 {
+                self.write_cursor.next(&mut self.statistics.write);
+            }
+            if !self.write_cursor.valid() {
+                // Key space ended. We are done here.
+                return Ok(());
+            }
+            {
+                let current_key = self.write_cursor.key(&mut self.statistics.write);
+                if !Key::is_user_key_eq(current_key, current_user_key.encoded().as_slice()) {
+                    // Found another user key. We are done here.
+                    return Ok(());
+                }
+            }
+        }
+
+        // We have not found another user key for now, so we directly `seek()`.
+        // After that, we must pointing to another key, or out of bound.
+        self.write_cursor.internal_seek(
+            &current_user_key.clone().append_ts(0),
+            &mut self.statistics.write,
+        )?;
+
+        Ok(())
+    }
+
+    /// Create the default cursor if it doesn't exist.
+    #[inline]
+    fn ensure_default_cursor(&mut self) -> Result<()> {
+        if self.default_cursor.is_some() {
+            return Ok(());
+        }
+        let cursor = CursorBuilder::new(&self.snapshot, CF_DEFAULT)
+            .range(self.lower_bound.take(), self.upper_bound.take())
+            .fill_cache(self.fill_cache)
+            .build()?;
+        self.default_cursor = Some(cursor);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use storage::mvcc::tests::*;
+    use storage::{Engine, TestEngineBuilder};
+
+    fn create_snapshot<E: Engine>(engine: &E) -> E::Snap {
+        engine.snapshot(&Default::default()).unwrap()
+    }
+
+    #[test]
+    fn test_multiple_versions_in_window() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+
+        must_prewrite_put(&engine, b"a", b"a_v1", b"a", 5);
+        must_commit(&engine, b"a", 5, 10);
+        must_prewrite_put(&engine, b"a", b"a_v2", b"a", 15);
+        must_commit(&engine, b"a", 15, 20);
+        must_prewrite_put(&engine, b"a", b"a_v3", b"a", 25);
+        must_commit(&engine, b"a", 25, 30);
+
+        let snapshot = create_snapshot(&engine);
+        let mut scanner = DeltaScannerBuilder::new(snapshot, 0, 100).build().unwrap();
+
+        // All three versions of `a` are replayed, newest `commit_ts` first.
+        assert_eq!(
+            scanner.read_next().unwrap(),
+            Some((Key::from_raw(b"a"), 30, WriteType::Put, Some(b"a_v3".to_vec())))
+        );
+        assert_eq!(
+            scanner.read_next().unwrap(),
+            Some((Key::from_raw(b"a"), 20, WriteType::Put, Some(b"a_v2".to_vec())))
+        );
+        assert_eq!(
+            scanner.read_next().unwrap(),
+            Some((Key::from_raw(b"a"), 10, WriteType::Put, Some(b"a_v1".to_vec())))
+        );
+        assert_eq!(scanner.read_next().unwrap(), None);
+    }
+
+    #[test]
+    fn test_window_is_half_open() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+
+        must_prewrite_put(&engine, b"a", b"a_v1", b"a", 5);
+        must_commit(&engine, b"a", 5, 10);
+        must_prewrite_put(&engine, b"a", b"a_v2", b"a", 15);
+        must_commit(&engine, b"a", 15, 20);
+        must_prewrite_put(&engine, b"a", b"a_v3", b"a", 25);
+        must_commit(&engine, b"a", 25, 30);
+
+        let snapshot = create_snapshot(&engine);
+        // `start_ts` is inclusive: the write committed exactly at 10 is included.
+        // `end_ts` is exclusive: the write committed exactly at 30 is excluded.
+        let mut scanner = DeltaScannerBuilder::new(snapshot, 10, 30).build().unwrap();
+
+        assert_eq!(
+            scanner.read_next().unwrap(),
+            Some((Key::from_raw(b"a"), 20, WriteType::Put, Some(b"a_v2".to_vec())))
+        );
+        assert_eq!(
+            scanner.read_next().unwrap(),
+            Some((Key::from_raw(b"a"), 10, WriteType::Put, Some(b"a_v1".to_vec())))
+        );
+        assert_eq!(scanner.read_next().unwrap(), None);
+    }
+
+    #[test]
+    fn test_delete_yields_no_value() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+
+        must_prewrite_put(&engine, b"a", b"a_v1", b"a", 5);
+        must_commit(&engine, b"a", 5, 10);
+        must_prewrite_delete(&engine, b"a", b"a", 15);
+        must_commit(&engine, b"a", 15, 20);
+
+        let snapshot = create_snapshot(&engine);
+        let mut scanner = DeltaScannerBuilder::new(snapshot, 0, 100).build().unwrap();
+
+        assert_eq!(
+            scanner.read_next().unwrap(),
+            Some((Key::from_raw(b"a"), 20, WriteType::Delete, None))
+        );
+        assert_eq!(
+            scanner.read_next().unwrap(),
+            Some((Key::from_raw(b"a"), 10, WriteType::Put, Some(b"a_v1".to_vec())))
+        );
+        assert_eq!(scanner.read_next().unwrap(), None);
+    }
+
+    #[test]
+    fn test_lock_and_rollback_are_skipped() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+
+        must_prewrite_put(&engine, b"a", b"a_v1", b"a", 5);
+        must_commit(&engine, b"a", 5, 10);
+        must_prewrite_put(&engine, b"a", b"a_v2", b"a", 15);
+        must_rollback(&engine, b"a", 15);
+        must_prewrite_lock(&engine, b"a", b"a", 25);
+        must_commit(&engine, b"a", 25, 30);
+
+        let snapshot = create_snapshot(&engine);
+        let mut scanner = DeltaScannerBuilder::new(snapshot, 0, 100).build().unwrap();
+
+        // The `Lock` and `Rollback` writes carry no user-visible change, so only the `Put` is
+        // emitted, even though all three writes fall inside the window.
+        assert_eq!(
+            scanner.read_next().unwrap(),
+            Some((Key::from_raw(b"a"), 10, WriteType::Put, Some(b"a_v1".to_vec())))
+        );
+        assert_eq!(scanner.read_next().unwrap(), None);
+    }
+
+    #[test]
+    fn test_short_value_and_default_cf_value() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+
+        // A short value is carried inline in the write record.
+        must_prewrite_put(&engine, b"a", b"short", b"a", 5);
+        must_commit(&engine, b"a", 5, 10);
+        // A long value spills into the default CF.
+        let long_value = vec![b'v'; 256];
+        must_prewrite_put(&engine, b"a", &long_value, b"a", 15);
+        must_commit(&engine, b"a", 15, 20);
+
+        let snapshot = create_snapshot(&engine);
+        let mut scanner = DeltaScannerBuilder::new(snapshot, 0, 100).build().unwrap();
+
+        assert_eq!(
+            scanner.read_next().unwrap(),
+            Some((Key::from_raw(b"a"), 20, WriteType::Put, Some(long_value)))
+        );
+        assert_eq!(
+            scanner.read_next().unwrap(),
+            Some((Key::from_raw(b"a"), 10, WriteType::Put, Some(b"short".to_vec())))
+        );
+        assert_eq!(scanner.read_next().unwrap(), None);
+    }
+
+    #[test]
+    fn test_range_bounds() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+
+        for (key, value) in &[(b"a" as &[u8], b"a_v" as &[u8]), (b"b", b"b_v"), (b"c", b"c_v")] {
+            must_prewrite_put(&engine, *key, *value, *key, 5);
+            must_commit(&engine, *key, 5, 10);
+        }
+
+        let snapshot = create_snapshot(&engine);
+        let mut scanner = DeltaScannerBuilder::new(snapshot, 0, 100)
+            .range(
+                Some(Key::from_raw(b"a").into_encoded()),
+                Some(Key::from_raw(b"c").into_encoded()),
+            )
+            .build()
+            .unwrap();
+
+        // Lower bound `a` is inclusive, upper bound `c` is exclusive.
+        assert_eq!(
+            scanner.read_next().unwrap(),
+            Some((Key::from_raw(b"a"), 10, WriteType::Put, Some(b"a_v".to_vec())))
+        );
+        assert_eq!(
+            scanner.read_next().unwrap(),
+            Some((Key::from_raw(b"b"), 10, WriteType::Put, Some(b"b_v".to_vec())))
+        );
+        assert_eq!(scanner.read_next().unwrap(), None);
+    }
+}
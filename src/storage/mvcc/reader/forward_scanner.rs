@@ -91,10 +91,18 @@ impl<S: Snapshot> ForwardScannerBuilder<S> {
 
     /// Build `ForwardScanner` from the current configuration.
     pub fn build(self) -> Result<ForwardScanner<S>> {
-        let lock_cursor = CursorBuilder::new(&self.snapshot, CF_LOCK)
-            .range(self.lower_bound.clone(), self.upper_bound.clone())
-            .fill_cache(self.fill_cache)
-            .build()?;
+        // Locks never produce conflicts under RC, so there is no need to even open the lock CF:
+        // this saves a seek and a `next()` per key on every iteration.
+        let lock_cursor = if self.isolation_level == IsolationLevel::RC {
+            None
+        } else {
+            Some(
+                CursorBuilder::new(&self.snapshot, CF_LOCK)
+                    .range(self.lower_bound.clone(), self.upper_bound.clone())
+                    .fill_cache(self.fill_cache)
+                    .build()?,
+            )
+        };
 
         let write_cursor = CursorBuilder::new(&self.snapshot, CF_WRITE)
             .range(self.lower_bound.clone(), self.upper_bound.clone())
@@ -138,7 +146,9 @@ pub struct ForwardScanner<S: Snapshot> {
 
     ts: u64,
 
-    lock_cursor: Cursor<S::Iter>,
+    /// `None` when `isolation_level` is `RC`: locks never produce conflicts under RC, so there
+    /// is no point opening the lock CF at all.
+    lock_cursor: Option<Cursor<S::Iter>>,
     write_cursor: Cursor<S::Iter>,
 
     /// `default cursor` is lazy created only when it's needed.
@@ -156,6 +166,29 @@ impl<S: Snapshot> ForwardScanner<S> {
         ::std::mem::replace(&mut self.statistics, Statistics::default())
     }
 
+    /// Get up to `expected_rows` key-value pairs, in forward order.
+    ///
+    /// This is purely a loop around `read_next`, collecting visible pairs until either
+    /// `expected_rows` is reached or the underlying cursors are exhausted. It preserves the
+    /// exact same lock-checking, isolation-level, and version-resolution semantics as
+    /// `read_next`; callers that would otherwise call `read_next` thousands of times (e.g. the
+    /// coprocessor table-scan path) can use this to amortize the per-row match and call
+    /// `take_statistics()` once per batch instead of once per row.
+    ///
+    /// On error (e.g. `KeyLocked`), the whole batch is discarded and the error is returned, the
+    /// same as the underlying `read_next` call that produced it — this is all-or-nothing, not a
+    /// partial batch.
+    pub fn read_next_batch(&mut self, expected_rows: usize) -> Result<Vec<(Key, Value)>> {
+        let mut rows = Vec::with_capacity(expected_rows);
+        while rows.len() < expected_rows {
+            match self.read_next()? {
+                Some(row) => rows.push(row),
+                None => break,
+            }
+        }
+        Ok(rows)
+    }
+
     /// Get the next key-value pair, in forward order.
     pub fn read_next(&mut self) -> Result<Option<(Key, Value)>> {
         if !self.is_started {
@@ -165,16 +198,17 @@ impl<S: Snapshot> ForwardScanner<S> {
                 &Key::from_encoded(self.lower_bound.as_ref().unwrap().clone()),
                 &mut self.statistics.write,
             )?;
-            self.lock_cursor.seek(
-                &Key::from_encoded(self.lower_bound.as_ref().unwrap().clone()),
-                &mut self.statistics.lock,
-            )?;
+            if let Some(lock_cursor) = self.lock_cursor.as_mut() {
+                lock_cursor.seek(
+                    &Key::from_encoded(self.lower_bound.as_ref().unwrap().clone()),
+                    &mut self.statistics.lock,
+                )?;
+            }
             self.is_started = true;
         }
 
-        // The general idea is to simultaneously step write cursor and lock cursor.
-
-        // TODO: We don't need to seek lock CF if isolation level is RC.
+        // The general idea is to simultaneously step write cursor and lock cursor. Under RC,
+        // `self.lock_cursor` is `None` and only the write cursor is consulted.
 
         loop {
             // `current_user_key` is `min(user_key(write_cursor), lock_cursor)`, indicating
@@ -195,10 +229,11 @@ impl<S: Snapshot> ForwardScanner<S> {
                 } else {
                     None
                 };
-                let l_key = if self.lock_cursor.valid() {
-                    Some(self.lock_cursor.key(&mut self.statistics.lock))
-                } else {
-                    None
+                let l_key = match self.lock_cursor.as_mut() {
+                    Some(lock_cursor) if lock_cursor.valid() => {
+                        Some(lock_cursor.key(&mut self.statistics.lock))
+                    }
+                    _ => None,
                 };
 
                 // `res` is `(current_user_key_slice, has_write, has_lock)`
@@ -260,22 +295,19 @@ impl<S: Snapshot> ForwardScanner<S> {
             let mut met_next_user_key = false;
 
             if has_lock {
-                match self.isolation_level {
-                    IsolationLevel::SI => {
-                        assert!(self.lock_cursor.valid());
-                        let lock = {
-                            let lock_value = self.lock_cursor.value(&mut self.statistics.lock);
-                            Lock::parse(lock_value)?
-                        };
-                        match super::util::check_lock(&current_user_key, self.ts, &lock)? {
-                            CheckLockResult::Locked(e) => result = Err(e),
-                            CheckLockResult::NotLocked => {}
-                            CheckLockResult::Ignored(ts) => get_ts = ts,
-                        }
-                    }
-                    IsolationLevel::RC => {}
+                // `has_lock` can only be `true` when `self.lock_cursor` is `Some`, i.e. under SI.
+                let lock_cursor = self.lock_cursor.as_mut().unwrap();
+                assert!(lock_cursor.valid());
+                let lock = {
+                    let lock_value = lock_cursor.value(&mut self.statistics.lock);
+                    Lock::parse(lock_value)?
+                };
+                match super::util::check_lock(&current_user_key, self.ts, &lock)? {
+                    CheckLockResult::Locked(e) => result = Err(e),
+                    CheckLockResult::NotLocked => {}
+                    CheckLockResult::Ignored(ts) => get_ts = ts,
                 }
-                self.lock_cursor.next(&mut self.statistics.lock);
+                lock_cursor.next(&mut self.statistics.lock);
             }
             if has_write {
                 // We don't need to read version if there is a lock error already.
@@ -392,6 +424,17 @@ impl<S: Snapshot> ForwardScanner<S> {
     /// directly. Otherwise there will be a default CF look up.
     ///
     /// The implementation is the same as `PointGetter::load_data_by_write`.
+    ///
+    /// Note: a prefix-bloom short-circuit was considered for the default-CF lookup below, but
+    /// was dropped as a no-op and not reintroduced. `load_data_by_write` is only reached for a
+    /// `Put` write with no short value, and the write protocol guarantees that such a write
+    /// always has its payload in the default CF — so a "definitely absent" filter result here
+    /// can only mean corruption, never a legitimate skip. The same is true earlier in `get()`:
+    /// by the time it runs, the write cursor is already positioned on an existing write of
+    /// `user_key` (see `has_write` in `read_next`), so presence is already established by
+    /// cursor state, not something a prefix filter could usefully answer "no" to. A bloom/filter
+    /// short-circuit would pay off for a cold point lookup (e.g. `PointGetter`, which seeks into
+    /// a CF without already knowing a key is there), not for this already-iterating scanner.
     #[inline]
     fn load_data_by_write(&mut self, write: Write, user_key: &Key) -> Result<Value> {
         if self.omit_value {